@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::hash::{Hash, ParseError};
+
+/// A disk-backed cache of `Hash` values, keyed by the SHA-1 digest of the source file's raw
+/// bytes. Entries are stored zlib-compressed so large image libraries don't pay repeated
+/// decode + resize + hash work on every run.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Opens (creating if necessary) a cache rooted at `dir`.
+    pub fn new(dir: PathBuf) -> Cache {
+        std::fs::create_dir_all(&dir).expect("failed to create cache directory");
+        Cache { dir }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Returns the cached `Hash` for `key`, if present.
+    pub fn get(&self, key: &str) -> Option<Hash> {
+        let compressed = std::fs::read(self.entry_path(key)).ok()?;
+        let bytes = decompress(&compressed).ok()?;
+        deserialize_entry(&bytes).ok()
+    }
+
+    /// Writes `hash` to the cache under `key`, overwriting any existing entry.
+    pub fn put(&self, key: &str, hash: &Hash) {
+        let bytes = serialize_entry(hash);
+        let compressed = compress(&bytes);
+        std::fs::write(self.entry_path(key), compressed)
+            .expect("failed to write cache entry");
+    }
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).expect("zlib compression failed");
+    encoder.finish().expect("zlib compression failed")
+}
+
+fn decompress(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+// Entry layout: dimension (4 bytes, little-endian u32), `to_bytes()` (self-describing: its
+// own bit-count prefix plus the packed bits), subarea_medians (4 bytes, row-major [0][0],
+// [1][0], [0][1], [1][1]), then a flag byte (1 if grayimage256 follows) and, when set, the
+// raw grayscale bytes for weighted-distance comparisons.
+fn serialize_entry(hash: &Hash) -> Vec<u8> {
+    let packed = hash.to_bytes();
+    let mut buf = Vec::with_capacity(4 + packed.len() + 4 + 1 + hash.grayimage256.len());
+
+    buf.extend_from_slice(&hash.dimension.to_le_bytes());
+    buf.extend_from_slice(&packed);
+    buf.push(hash.subarea_medians[0][0]);
+    buf.push(hash.subarea_medians[1][0]);
+    buf.push(hash.subarea_medians[0][1]);
+    buf.push(hash.subarea_medians[1][1]);
+    buf.push(1);
+    buf.extend_from_slice(&hash.grayimage256);
+
+    buf
+}
+
+fn deserialize_entry(buf: &[u8]) -> Result<Hash, ParseError> {
+    if buf.len() < 4 + 4 {
+        return Err(ParseError::InvalidLength(buf.len()));
+    }
+
+    let dimension = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let len = (dimension as usize) * (dimension as usize);
+
+    let bitlen = u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
+    let packed_len = 4 + (bitlen + 7) / 8;
+
+    let medians_offset = 4 + packed_len;
+    if buf.len() < medians_offset + 4 + 1 {
+        return Err(ParseError::InvalidLength(buf.len()));
+    }
+
+    let mut hash = Hash::from_bytes(&buf[4..medians_offset], dimension)?;
+    hash.subarea_medians[0][0] = buf[medians_offset];
+    hash.subarea_medians[1][0] = buf[medians_offset + 1];
+    hash.subarea_medians[0][1] = buf[medians_offset + 2];
+    hash.subarea_medians[1][1] = buf[medians_offset + 3];
+
+    let has_grayimage = buf[medians_offset + 4] == 1;
+    if has_grayimage {
+        let gray_offset = medians_offset + 5;
+        if buf.len() < gray_offset + len {
+            return Err(ParseError::InvalidLength(buf.len()));
+        }
+        let gray = &buf[gray_offset..(gray_offset + len)];
+        hash.grayimage256.copy_from_slice(gray);
+    }
+
+    Ok(hash)
+}
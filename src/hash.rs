@@ -1,77 +1,261 @@
+use std::fmt;
 use std::path::PathBuf;
 use image::GrayImage;
+use sha1::{Digest, Sha1};
 
+use crate::cache::Cache;
 use crate::editing::{preprocess_image, mirror_by_brightest_pixel};
 use crate::hashmath::hex_to_binary;
 use crate::SIZE;
 
-const HASHLEN: usize = (SIZE*SIZE) as usize;
-
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Hash {
-    pub grayimage256: [u8; HASHLEN],
-    pub binary256: [u8; HASHLEN],
+    pub grayimage256: Vec<u8>,
+    pub binary256: Vec<u8>,
     pub subarea_medians: [[u8; 2]; 2],
+    pub dimension: u32,
 }
 
 impl Hash {
     pub fn new() -> Hash {
+        Hash::new_sized(SIZE)
+    }
+
+    /// `dimension` must be even and at least 4: `set_subarea_medians` needs each quadrant
+    /// non-empty and `from_grayimage_dct` needs at least one non-DC low-frequency
+    /// coefficient, both of which panic on out-of-bounds indexing for smaller dimensions.
+    fn new_sized(dimension: u32) -> Hash {
+        assert!(
+            dimension >= 4 && dimension % 2 == 0,
+            "Hash dimension must be an even number >= 4, got {}",
+            dimension
+        );
+
+        let len = (dimension * dimension) as usize;
         Hash {
-            grayimage256: [0; HASHLEN],
-            binary256: [0; HASHLEN],
+            grayimage256: vec![0; len],
+            binary256: vec![0; len],
             subarea_medians: [[0; 2]; 2],
+            dimension,
         }
     }
 
     pub fn from_path(path: &PathBuf) -> Hash {
+        Hash::from_path_with_canonicalization(path, Canonicalization::MirrorByBrightest)
+    }
+
+    /// Like `from_path`, but lets the caller choose how rotation/reflection invariance is
+    /// applied instead of always mirroring by the brightest pixel. `EightFold` costs 8x the
+    /// hashing work of the other modes, so only opt into it when the dataset may contain
+    /// rotated or mirrored duplicates.
+    pub fn from_path_with_canonicalization(path: &PathBuf, canon: Canonicalization) -> Hash {
         // Processing raw image
-        let mut img = preprocess_image(path);
-        let img = mirror_by_brightest_pixel(&mut img);
+        let img = preprocess_image(path);
+
+        match canon {
+            Canonicalization::None => Hash::from_grayimage(img),
+            Canonicalization::MirrorByBrightest => {
+                let mut img = img;
+                let mirrored = mirror_by_brightest_pixel(&mut img);
+                Hash::from_grayimage(mirrored.to_owned())
+            }
+            Canonicalization::EightFold => Hash::from_grayimage_eightfold(img),
+        }
+    }
 
-        Hash::from_grayimage(img.to_owned())
+    /// Hashes all 8 dihedral orientations (4 rotations x 2 mirrors) of `img` and keeps the
+    /// lexicographically smallest `binary256`, guaranteeing identical output for any
+    /// rotated/flipped version of the same image.
+    fn from_grayimage_eightfold(img: GrayImage) -> Hash {
+        dihedral_orientations(img)
+            .into_iter()
+            .map(Hash::from_grayimage)
+            .min_by(|a, b| a.binary256.cmp(&b.binary256))
+            .expect("dihedral_orientations always yields 8 images")
+    }
+
+    /// Like `from_path`, but consults `cache` first and writes through on a miss, keyed by
+    /// the SHA-1 digest of the file's raw bytes. Saves the decode + resize + hash work that
+    /// dominates runtime when the same image library is hashed repeatedly.
+    pub fn from_path_cached(path: &PathBuf, cache: &Cache) -> Hash {
+        let bytes = std::fs::read(path).expect("failed to read image file");
+
+        let mut hasher = Sha1::new();
+        hasher.update(&bytes);
+        let key = format!("{:x}", hasher.finalize());
+
+        if let Some(hash) = cache.get(&key) {
+            return hash;
+        }
+
+        let hash = Hash::from_path(path);
+        cache.put(&key, &hash);
+        hash
     }
 
     pub fn from_grayimage(img: GrayImage) -> Hash {
-        let mut hash = Hash::new();
+        Hash::from_grayimage_sized(img, SIZE)
+    }
+
+    /// Like `from_grayimage`, but at a caller-chosen `dimension` instead of the compile-time
+    /// `SIZE`. `img` must already be `dimension x dimension`. Larger dimensions give finer
+    /// discrimination for near-duplicate detection; smaller ones are faster and more tolerant.
+    pub fn from_grayimage_sized(img: GrayImage, dimension: u32) -> Hash {
+        let mut hash = Hash::new_sized(dimension);
 
         // Saving grayscale image to array (necessary for weighted distance calculation)
         hash.set_grayimage(img);
 
         // Setting the subarea medians
         hash.set_subarea_medians();
-        
+
         // Calculating Hash from grayscale image
         hash.set_binary_hash_from_grayimage();
 
         hash
     }
 
-    pub fn from_hexhash(hexhash: &[char; HASHLEN/4]) -> Hash {
-        let mut binaryhash = [0; HASHLEN];
+    /// Computes a hash from `img` using the algorithm selected by `ty`, instead of always
+    /// going through the quadrant-median forbild algorithm. The hash's dimension is taken
+    /// from `img`'s width; `img` must be square.
+    pub fn with_type(img: GrayImage, ty: HashType) -> Hash {
+        assert_eq!(
+            img.width(), img.height(),
+            "with_type requires a square image, got {}x{}", img.width(), img.height()
+        );
+
+        let dimension = img.width();
+        match ty {
+            HashType::Forbild => Hash::from_grayimage_sized(img, dimension),
+            HashType::Mean => Hash::from_grayimage_mean(img, dimension),
+            HashType::Gradient => Hash::from_grayimage_gradient(img, dimension),
+            HashType::Dct => Hash::from_grayimage_dct(img, dimension),
+        }
+    }
+
+    /// aHash: thresholds every pixel against the single global median of `grayimage256`.
+    fn from_grayimage_mean(img: GrayImage, dimension: u32) -> Hash {
+        let mut hash = Hash::new_sized(dimension);
+        hash.set_grayimage(img);
+        hash.set_subarea_medians();
+
+        let mut sorted = hash.grayimage256.clone();
+        sorted.sort();
+        let median = sorted[sorted.len() / 2];
+
+        for i in 0..hash.grayimage256.len() {
+            hash.binary256[i] = match hash.grayimage256[i] >= median {
+                true => 1,
+                false => 0,
+            };
+        }
+
+        hash
+    }
+
+    /// dHash: sets bit i to 1 when pixel i is brighter than its right-hand neighbor within
+    /// each row. The last column of each row has no right-hand neighbor and is left at 0.
+    fn from_grayimage_gradient(img: GrayImage, dimension: u32) -> Hash {
+        let mut hash = Hash::new_sized(dimension);
+        hash.set_grayimage(img);
+        hash.set_subarea_medians();
+
+        for y in 0..dimension {
+            for x in 0..(dimension - 1) {
+                let i = (x + dimension * y) as usize;
+                let j = (x + 1 + dimension * y) as usize;
+
+                hash.binary256[i] = match hash.grayimage256[i] > hash.grayimage256[j] {
+                    true => 1,
+                    false => 0,
+                };
+            }
+        }
+
+        hash
+    }
+
+    /// pHash: applies a separable 2-D DCT-II to `grayimage256`, keeps the low-frequency
+    /// top-left quadrant excluding the DC coefficient, and thresholds those coefficients
+    /// against their median. Unlike the other `HashType` variants, the resulting `binary256`
+    /// is packed contiguously from index 0 and is shorter than `dimension * dimension` (e.g.
+    /// 63 bits for `dimension == 16`), since only the low-frequency coefficients carry signal.
+    fn from_grayimage_dct(img: GrayImage, dimension: u32) -> Hash {
+        let mut hash = Hash::new_sized(dimension);
+        hash.set_grayimage(img);
+        hash.set_subarea_medians();
+
+        let n = dimension as usize;
+        let block = (n / 2).max(1);
+
+        let mut matrix: Vec<Vec<f64>> = (0..n)
+            .map(|y| (0..n).map(|x| hash.grayimage256[x + n * y] as f64).collect())
+            .collect();
+
+        for row in matrix.iter_mut() {
+            *row = dct_1d(row);
+        }
+        for x in 0..n {
+            let column: Vec<f64> = (0..n).map(|y| matrix[y][x]).collect();
+            let transformed = dct_1d(&column);
+            for (y, val) in transformed.into_iter().enumerate() {
+                matrix[y][x] = val;
+            }
+        }
+
+        let mut coefficients = Vec::with_capacity(block * block - 1);
+        for y in 0..block {
+            for x in 0..block {
+                if x == 0 && y == 0 {
+                    continue;
+                }
+                coefficients.push(matrix[y][x]);
+            }
+        }
+
+        let mut sorted = coefficients.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = sorted[sorted.len() / 2];
+
+        hash.binary256 = coefficients
+            .into_iter()
+            .map(|c| match c > median {
+                true => 1,
+                false => 0,
+            })
+            .collect();
+
+        hash
+    }
+
+    /// Reconstructs a `Hash` of `dimension` from a hex string previously produced by
+    /// `to_hex`/`to_string_hex`.
+    pub fn from_hexhash(hexhash: &[char], dimension: u32) -> Hash {
+        let mut hash = Hash::new_sized(dimension);
 
         for (i, hexval) in hexhash.iter().enumerate() {
             let binaries = hex_to_binary(hexval).unwrap();
             for (j, b) in binaries.iter().enumerate() {
-                binaryhash[i+j] = *b;
+                hash.binary256[i*4 + j] = *b;
             }
         }
 
-        let mut hash = Hash::new();
-        hash.binary256 = binaryhash;
         hash
     }
 
     fn set_grayimage(&mut self, img: GrayImage) {
         for (x, y, pix) in img.enumerate_pixels() {
-            self.grayimage256[(x + SIZE*y) as usize] = pix[0];
+            self.grayimage256[(x + self.dimension*y) as usize] = pix[0];
         }
-    } 
+    }
 
     pub fn get_subarea(&self, i: usize) -> SubArea {
+        let len = self.grayimage256.len();
+
         // Subarea top and bottom left
-        if (i as u32)%SIZE < SIZE/2 {
+        if (i as u32)%self.dimension < self.dimension/2 {
             // Subarea top left
-            if i < HASHLEN/2 {
+            if i < len/2 {
                 SubArea::TopLeft
             }
             // Subarea bottom left
@@ -82,7 +266,7 @@ impl Hash {
         // Subarea top and bottom right
         else {
             // Subarea top right
-            if i < HASHLEN/2 {
+            if i < len/2 {
                 SubArea::TopRight
             }
             // Subarea bottom right
@@ -93,10 +277,10 @@ impl Hash {
     }
 
     fn set_subarea_medians(&mut self) {
-        let mut top_left = Vec::with_capacity(64);
-        let mut top_right = Vec::with_capacity(64);
-        let mut bot_left = Vec::with_capacity(64);
-        let mut bot_right = Vec::with_capacity(64);
+        let mut top_left = Vec::with_capacity(self.grayimage256.len() / 4);
+        let mut top_right = Vec::with_capacity(self.grayimage256.len() / 4);
+        let mut bot_left = Vec::with_capacity(self.grayimage256.len() / 4);
+        let mut bot_right = Vec::with_capacity(self.grayimage256.len() / 4);
 
         for (i, val) in self.grayimage256.iter().enumerate() {
             match self.get_subarea(i) {
@@ -114,10 +298,10 @@ impl Hash {
         bot_right.sort_by(|a, b| a.partial_cmp(b).unwrap());
 
         // Setting the median value
-        self.subarea_medians[0][0] = top_left[32];
-        self.subarea_medians[1][0] = top_right[32];
-        self.subarea_medians[0][1] = bot_left[32];
-        self.subarea_medians[1][1] = bot_right[32];
+        self.subarea_medians[0][0] = top_left[top_left.len()/2];
+        self.subarea_medians[1][0] = top_right[top_right.len()/2];
+        self.subarea_medians[0][1] = bot_left[bot_left.len()/2];
+        self.subarea_medians[1][1] = bot_right[bot_right.len()/2];
     }
 
     fn set_binary_hash_from_grayimage(&mut self) {
@@ -131,10 +315,57 @@ impl Hash {
             self.binary256[i] = match *val >= median {
                 true => 1,
                 false => 0
-            }; 
+            };
         }
     }
 
+    /// Counts the number of differing bits between `self` and `other`.
+    pub fn hamming_distance(&self, other: &Hash) -> u32 {
+        self.binary256
+            .iter()
+            .zip(other.binary256.iter())
+            .filter(|(a, b)| a != b)
+            .count() as u32
+    }
+
+    /// Like `hamming_distance`, but weighs every differing bit by how confidently it was set:
+    /// a bit that flipped right at its subarea's median contributes less than one that flipped
+    /// far from it, giving a perception-aware tie-breaker on top of raw Hamming distance.
+    ///
+    /// Only meaningful on a `self` freshly computed from an image by the forbild algorithm
+    /// (`from_grayimage`/`from_path`/`with_type` with `HashType::Forbild`); the other
+    /// `HashType` variants do not set their bits against the subarea medians, and the weights
+    /// are computed the same way regardless. `grayimage256` and `subarea_medians` are all-zero on any
+    /// `Hash` reconstructed via `from_hexhash`, `from_bytes` or `from_base64`, which would
+    /// otherwise make every deviation silently compute to 0.0 (perfect similarity). Returns
+    /// `None` instead when `self`'s gray data looks unset.
+    ///
+    /// Also note this is asymmetric: it only reads `self`'s gray data and subarea medians, so
+    /// `a.weighted_distance(b)` and `b.weighted_distance(a)` can differ.
+    pub fn weighted_distance(&self, other: &Hash) -> Option<f64> {
+        if self.grayimage256.iter().all(|&v| v == 0) {
+            return None;
+        }
+
+        let mut distance = 0.0;
+
+        for i in 0..self.binary256.len() {
+            if self.binary256[i] != other.binary256[i] {
+                let median = match self.get_subarea(i) {
+                    SubArea::TopLeft => self.subarea_medians[0][0],
+                    SubArea::TopRight => self.subarea_medians[1][0],
+                    SubArea::BottomLeft => self.subarea_medians[0][1],
+                    SubArea::BottomRight => self.subarea_medians[1][1],
+                };
+
+                let deviation = (self.grayimage256[i] as f64 - median as f64).abs();
+                distance += deviation / 255.0;
+            }
+        }
+
+        Some(distance)
+    }
+
     pub fn to_string(&self) -> String {
         self.binary256
             .iter()
@@ -142,11 +373,14 @@ impl Hash {
             .collect()
     }
 
-    pub fn to_hex(&self) -> [char; HASHLEN/4] {
-        let mut hex_hash: [char; HASHLEN/4] = ['0'; HASHLEN/4];
+    /// Encodes `binary256` as hex, 4 bits per character. The output length follows the length
+    /// of `binary256` rather than assuming a fixed 256-bit hash; a `binary256` whose length is
+    /// not a multiple of 4 (such as a `Dct`-type hash) cannot be encoded and exits the process.
+    pub fn to_hex(&self) -> Vec<char> {
+        let mut hex_hash = Vec::with_capacity(self.binary256.len() / 4);
 
-        for i in 0..(HASHLEN/4) {
-            let hexval = match self.binary256[(4*i)..(4*i+4)] {
+        for chunk in self.binary256.chunks(4) {
+            let hexval = match chunk {
                 [0, 0, 0, 0] => Some('0'),
                 [0, 0, 0, 1] => Some('1'),
                 [0, 0, 1, 0] => Some('2'),
@@ -167,7 +401,7 @@ impl Hash {
             };
 
             if hexval.is_some() {
-            hex_hash[i] = hexval.unwrap();
+                hex_hash.push(hexval.unwrap());
             } else {
                 eprintln!("ERROR: A part of the binary hash cannot be converted to hexadecimal.");
                 std::process::exit(1);
@@ -183,12 +417,144 @@ impl Hash {
             .collect::<Vec<String>>()
             .concat()
     }
+
+    /// Packs `binary256` into bytes, 8 bits per byte, MSB-first, prefixed with the bit count
+    /// as a little-endian `u32`. The prefix is required because `binary256` is not always
+    /// `dimension * dimension` bits long: a `Dct`-type hash (see `with_type`) packs only its
+    /// low-frequency coefficients, so `from_bytes` cannot otherwise recover how many of the
+    /// trailing bits are real data versus padding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bitlen = self.binary256.len() as u32;
+        let bytelen = (self.binary256.len() + 7) / 8;
+        let mut bytes = vec![0; 4 + bytelen];
+        bytes[0..4].copy_from_slice(&bitlen.to_le_bytes());
+
+        for (i, bit) in self.binary256.iter().enumerate() {
+            if *bit != 0 {
+                bytes[4 + i / 8] |= 1 << (7 - (i % 8));
+            }
+        }
+
+        bytes
+    }
+
+    /// Inverse of `to_bytes`, for a hash of the given `dimension`.
+    pub fn from_bytes(bytes: &[u8], dimension: u32) -> Result<Hash, ParseError> {
+        if bytes.len() < 4 {
+            return Err(ParseError::InvalidLength(bytes.len()));
+        }
+
+        let bitlen = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let expected_bytes = 4 + (bitlen + 7) / 8;
+        if bytes.len() != expected_bytes {
+            return Err(ParseError::InvalidLength(bytes.len()));
+        }
+
+        let mut hash = Hash::new_sized(dimension);
+        hash.binary256 = vec![0; bitlen];
+
+        for i in 0..bitlen {
+            hash.binary256[i] = (bytes[4 + i / 8] >> (7 - (i % 8))) & 1;
+        }
+
+        Ok(hash)
+    }
+
+    /// Base64-encodes the packed `to_bytes` representation: 48 characters for a 256-bit hash,
+    /// against 64 from `to_hex` and 256 from `to_string`.
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.to_bytes())
+    }
+
+    /// Inverse of `to_base64`, for a hash of the given `dimension`.
+    pub fn from_base64(s: &str, dimension: u32) -> Result<Hash, ParseError> {
+        let bytes = base64::decode(s).map_err(ParseError::InvalidBase64)?;
+        Hash::from_bytes(&bytes, dimension)
+    }
 }
 
+/// Errors that can occur while parsing a `Hash` from a serialized representation.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The decoded byte buffer did not have the expected length.
+    InvalidLength(usize),
+    /// The input was not valid base64.
+    InvalidBase64(base64::DecodeError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidLength(len) => {
+                write!(f, "unexpected serialized hash length: {} bytes", len)
+            }
+            ParseError::InvalidBase64(e) => write!(f, "invalid base64: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 
 pub enum SubArea {
     TopLeft,
     TopRight,
     BottomLeft,
     BottomRight,
-}
\ No newline at end of file
+}
+
+/// Selects which hashing algorithm `Hash::with_type` should run.
+pub enum HashType {
+    /// The quadrant-median algorithm used throughout this crate.
+    Forbild,
+    /// aHash: threshold against the global median pixel value.
+    Mean,
+    /// dHash: threshold on the gradient between neighboring pixels.
+    Gradient,
+    /// pHash: threshold on the low-frequency coefficients of a 2-D DCT.
+    Dct,
+}
+
+/// Selects how `Hash::from_path_with_canonicalization` makes a hash invariant to rotation
+/// and reflection.
+pub enum Canonicalization {
+    /// No canonicalization: hash the preprocessed image as-is.
+    None,
+    /// Mirror by the brightest pixel, as `from_path` has always done.
+    MirrorByBrightest,
+    /// Hash all 8 dihedral orientations and keep the lexicographically smallest result.
+    EightFold,
+}
+
+/// The 8 dihedral orientations of `img`: its 4 rotations, each with its own mirror.
+fn dihedral_orientations(img: GrayImage) -> Vec<GrayImage> {
+    use image::imageops::{flip_horizontal, rotate90, rotate180, rotate270};
+
+    let rot0 = img;
+    let rot90 = rotate90(&rot0);
+    let rot180 = rotate180(&rot0);
+    let rot270 = rotate270(&rot0);
+
+    let flip0 = flip_horizontal(&rot0);
+    let flip90 = flip_horizontal(&rot90);
+    let flip180 = flip_horizontal(&rot180);
+    let flip270 = flip_horizontal(&rot270);
+
+    vec![rot0, rot90, rot180, rot270, flip0, flip90, flip180, flip270]
+}
+
+/// 1-D DCT-II: `X_k = sum_n(x_n * cos(pi/N * (n+0.5) * k))`.
+fn dct_1d(input: &[f64]) -> Vec<f64> {
+    let n = input.len();
+    let mut output = vec![0.0; n];
+
+    for (k, out) in output.iter_mut().enumerate() {
+        let mut sum = 0.0;
+        for (x_n, val) in input.iter().enumerate() {
+            sum += val * ((std::f64::consts::PI / n as f64) * (x_n as f64 + 0.5) * k as f64).cos();
+        }
+        *out = sum;
+    }
+
+    output
+}